@@ -2,11 +2,14 @@
 extern crate alloc;
 
 use alloc::boxed::Box;
+use alloc::vec::Vec;
 use core::ffi::{c_char, c_int, c_uchar, c_ulonglong};
 use core::{slice, str};
 
-use regex_automata::meta::{Builder, Regex};
+use regex_automata::meta::{self, Builder, Regex};
+use regex_automata::util::captures::Captures;
 use regex_automata::util::syntax; // syntax::parse
+use regex_automata::{Input, PatternID, PatternSet};
 
 // -------- minimal bump allocator (no dealloc; enough for compile/match) --------
 use core::alloc::{GlobalAlloc, Layout};
@@ -16,6 +19,8 @@ struct BumpAlloc;
 const HEAP_SIZE: usize = 4 * 1024 * 1024;
 static mut HEAP: [u8; HEAP_SIZE] = [0; HEAP_SIZE];
 static OFF: AtomicUsize = AtomicUsize::new(0);
+// Largest OFF has ever reached, independent of rewinds; diagnostic only.
+static HIGH_WATER: AtomicUsize = AtomicUsize::new(0);
 
 unsafe impl GlobalAlloc for BumpAlloc {
     unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
@@ -28,7 +33,10 @@ unsafe impl GlobalAlloc for BumpAlloc {
             let new_off = aligned + size - base;
             if new_off > HEAP_SIZE { return core::ptr::null_mut(); }
             match OFF.compare_exchange(off, new_off, Ordering::SeqCst, Ordering::Relaxed) {
-                Ok(_) => return aligned as *mut u8,
+                Ok(_) => {
+                    HIGH_WATER.fetch_max(new_off, Ordering::Relaxed);
+                    return aligned as *mut u8;
+                }
                 Err(o) => off = o,
             }
         }
@@ -38,6 +46,28 @@ unsafe impl GlobalAlloc for BumpAlloc {
     }
 }
 
+/// Snapshot the current bump offset. Pass the result to `aifw_heap_rewind`
+/// to reclaim everything allocated after this point.
+#[no_mangle]
+pub extern "C" fn aifw_heap_reset() -> c_ulonglong {
+    OFF.load(Ordering::SeqCst) as c_ulonglong
+}
+
+/// Roll the bump allocator back to a checkpoint from `aifw_heap_reset` in
+/// O(1). This invalidates every handle (`AifwRegex`, `AifwRegexSet`,
+/// `AifwRegexIter`, ...) allocated after the checkpoint — using one after
+/// rewinding is undefined behavior.
+#[no_mangle]
+pub extern "C" fn aifw_heap_rewind(checkpoint: c_ulonglong) {
+    OFF.store(checkpoint as usize, Ordering::SeqCst);
+}
+
+/// The largest the bump offset has ever reached, for sizing `HEAP_SIZE`.
+#[no_mangle]
+pub extern "C" fn aifw_heap_high_water() -> c_ulonglong {
+    HIGH_WATER.load(Ordering::SeqCst) as c_ulonglong
+}
+
 #[global_allocator]
 static GLOBAL: BumpAlloc = BumpAlloc;
 
@@ -59,22 +89,22 @@ pub struct AifwRegex {
     re: Regex,
 }
 
+// Read a NUL-terminated C string into a `&str`, failing on invalid UTF-8.
+unsafe fn cstr_to_str<'a>(pattern: *const c_char) -> Option<&'a str> {
+    if pattern.is_null() { return None; }
+    let mut l = 0usize;
+    while *pattern.add(l) != 0 { l += 1; }
+    let bytes = slice::from_raw_parts(pattern as *const u8, l);
+    str::from_utf8(bytes).ok()
+}
+
 /// Compile the regular expression.
 /// Returns a handle; returns null on failure.
 #[no_mangle]
 pub extern "C" fn aifw_regex_compile(pattern: *const c_char) -> *mut AifwRegex {
-    if pattern.is_null() { return core::ptr::null_mut(); }
-
-    // compute C string length
-    let len = unsafe {
-        let mut l = 0usize;
-        while *pattern.add(l) != 0 { l += 1; }
-        l
-    };
-    let bytes = unsafe { slice::from_raw_parts(pattern as *const u8, len) };
-    let p = match str::from_utf8(bytes) {
-        Ok(s) => s,
-        Err(_) => return core::ptr::null_mut()
+    let p = match unsafe { cstr_to_str(pattern) } {
+        Some(s) => s,
+        None => return core::ptr::null_mut(),
     };
 
     let hir = match syntax::parse(p) {
@@ -88,6 +118,126 @@ pub extern "C" fn aifw_regex_compile(pattern: *const c_char) -> *mut AifwRegex {
     Box::into_raw(Box::new(AifwRegex { re }))
 }
 
+/// Bit flags for `aifw_regex_compile_flags`, mirroring rure's `rure_flags`.
+pub const AIFW_FLAG_CASEI: u32 = 1 << 0;
+pub const AIFW_FLAG_MULTI_LINE: u32 = 1 << 1;
+pub const AIFW_FLAG_DOTNL: u32 = 1 << 2;
+pub const AIFW_FLAG_SWAP_GREED: u32 = 1 << 3;
+pub const AIFW_FLAG_SPACE: u32 = 1 << 4;
+pub const AIFW_FLAG_UNICODE: u32 = 1 << 5;
+
+/// Compile the regular expression with syntax flags (see the `AIFW_FLAG_*`
+/// constants). Returns a handle; returns null on failure.
+#[no_mangle]
+pub extern "C" fn aifw_regex_compile_flags(pattern: *const c_char, flags: u32) -> *mut AifwRegex {
+    let p = match unsafe { cstr_to_str(pattern) } {
+        Some(s) => s,
+        None => return core::ptr::null_mut(),
+    };
+
+    let cfg = syntax::Config::new()
+        .case_insensitive(flags & AIFW_FLAG_CASEI != 0)
+        .multi_line(flags & AIFW_FLAG_MULTI_LINE != 0)
+        .dot_matches_new_line(flags & AIFW_FLAG_DOTNL != 0)
+        .swap_greed(flags & AIFW_FLAG_SWAP_GREED != 0)
+        .ignore_whitespace(flags & AIFW_FLAG_SPACE != 0)
+        .unicode(flags & AIFW_FLAG_UNICODE != 0);
+
+    let re = match Builder::new().syntax(cfg).build(p) {
+        Ok(r) => r,
+        Err(_) => return core::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(AifwRegex { re }))
+}
+
+/// Compile with explicit build limits so a pathological pattern fails
+/// gracefully instead of exhausting the static heap. A limit of 0 means
+/// "use the meta engine's default". Returns a handle; returns null on
+/// failure (including when a limit is exceeded during compilation).
+#[no_mangle]
+pub extern "C" fn aifw_regex_compile_opts(
+    pattern: *const c_char,
+    size_limit: c_ulonglong,
+    dfa_size_limit: c_ulonglong,
+) -> *mut AifwRegex {
+    let p = match unsafe { cstr_to_str(pattern) } {
+        Some(s) => s,
+        None => return core::ptr::null_mut(),
+    };
+
+    let mut cfg = meta::Config::new();
+    if size_limit != 0 {
+        cfg = cfg.nfa_size_limit(Some(size_limit as usize));
+    }
+    if dfa_size_limit != 0 {
+        cfg = cfg
+            .dfa_size_limit(Some(dfa_size_limit as usize))
+            .hybrid_cache_capacity(dfa_size_limit as usize);
+    }
+
+    let re = match Builder::new().configure(cfg).build(p) {
+        Ok(r) => r,
+        Err(_) => return core::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(AifwRegex { re }))
+}
+
+/// Compile the regular expression, reporting the reason for failure instead
+/// of collapsing every error into a null return.
+///
+/// On failure, formats a human-readable message into `err_buf` (truncated
+/// to `err_cap`, with `*err_written` always set to the length that would
+/// have been needed) and returns null. On success, `err_buf` and
+/// `*err_written` are left untouched.
+#[no_mangle]
+pub extern "C" fn aifw_regex_compile_err(
+    pattern: *const c_char,
+    err_buf: *mut c_uchar,
+    err_cap: c_ulonglong,
+    err_written: *mut c_ulonglong,
+) -> *mut AifwRegex {
+    use core::fmt::Write as _;
+
+    if err_written.is_null() { return core::ptr::null_mut(); }
+    let buf: &mut [u8] = if err_buf.is_null() || err_cap == 0 {
+        &mut []
+    } else {
+        unsafe { slice::from_raw_parts_mut(err_buf, err_cap as usize) }
+    };
+    let mut cur = Cursor::new(buf);
+
+    let p = match pattern.is_null() {
+        true => None,
+        false => unsafe { cstr_to_str(pattern) },
+    };
+    let p = match p {
+        Some(s) => s,
+        None => {
+            let _ = write!(cur, "pattern is null or not valid UTF-8");
+            unsafe { *err_written = cur.needed as c_ulonglong; }
+            return core::ptr::null_mut();
+        }
+    };
+
+    let hir = match syntax::parse(p) {
+        Ok(h) => h,
+        Err(e) => {
+            let _ = write!(cur, "{}", e);
+            unsafe { *err_written = cur.needed as c_ulonglong; }
+            return core::ptr::null_mut();
+        }
+    };
+    let re = match Builder::new().build_from_hir(&hir) {
+        Ok(r) => r,
+        Err(e) => {
+            let _ = write!(cur, "{}", e);
+            unsafe { *err_written = cur.needed as c_ulonglong; }
+            return core::ptr::null_mut();
+        }
+    };
+    Box::into_raw(Box::new(AifwRegex { re }))
+}
+
 #[no_mangle]
 pub extern "C" fn aifw_regex_free(ptr_re: *mut AifwRegex) {
     if !ptr_re.is_null() {
@@ -124,3 +274,495 @@ pub extern "C" fn aifw_regex_find(
         None => 0,
     }
 }
+
+/// Number of capture groups (including the implicit group 0) for this
+/// regex. Size `out_slots` in `aifw_regex_captures` to twice this value.
+/// Returns -1 if `ptr_re` is null.
+#[no_mangle]
+pub extern "C" fn aifw_regex_capture_count(ptr_re: *mut AifwRegex) -> c_int {
+    if ptr_re.is_null() { return -1; }
+    let re = unsafe { &*ptr_re };
+    re.re.group_info().group_len(PatternID::ZERO) as c_int
+}
+
+/// Look up the group index of a named capture group, e.g. `(?P<name>...)`;
+/// its span is at `out_slots[2*index]` in `aifw_regex_captures`.
+/// Returns the index on success, or a negative value if the regex is null,
+/// the name is not valid UTF-8, or no group by that name exists.
+#[no_mangle]
+pub extern "C" fn aifw_regex_capture_index(
+    ptr_re: *mut AifwRegex,
+    name_ptr: *const c_uchar,
+    name_len: c_ulonglong,
+) -> c_int {
+    if ptr_re.is_null() || name_ptr.is_null() { return -1; }
+    let re = unsafe { &*ptr_re };
+    let bytes = unsafe { slice::from_raw_parts(name_ptr, name_len as usize) };
+    let name = match str::from_utf8(bytes) {
+        Ok(s) => s,
+        Err(_) => return -1,
+    };
+    match re.re.group_info().to_index(PatternID::ZERO, name) {
+        Some(i) => i as c_int,
+        None => -1,
+    }
+}
+
+/// Find a match and report every capture group's span.
+///
+/// `out_slots` must have room for `2 * aifw_regex_capture_count(ptr_re)`
+/// `c_ulonglong`s; each group `i` fills `out_slots[2*i]`/`out_slots[2*i+1]`
+/// with its (start, end), or `c_ulonglong::MAX`/`c_ulonglong::MAX` if that
+/// group did not participate in the match.
+///
+/// Returns 1 if a match was found, 0 if not, and < 0 on error (including
+/// when `slots_len` is too small to hold every group).
+#[no_mangle]
+pub extern "C" fn aifw_regex_captures(
+    ptr_re: *mut AifwRegex,
+    hay_ptr: *const c_uchar,
+    hay_len: c_ulonglong,
+    start: c_ulonglong,
+    out_slots: *mut c_ulonglong,
+    slots_len: c_ulonglong,
+) -> c_int {
+    if ptr_re.is_null() || hay_ptr.is_null() || out_slots.is_null() {
+        return -1;
+    }
+    let re = unsafe { &*ptr_re };
+    let group_count = re.re.group_info().group_len(PatternID::ZERO);
+    if (slots_len as usize) < group_count * 2 {
+        return -2;
+    }
+
+    let hay = unsafe { slice::from_raw_parts(hay_ptr as *const u8, hay_len as usize) };
+    let s = core::cmp::min(start as usize, hay.len());
+    let sub = &hay[s..];
+
+    let mut caps = re.re.create_captures();
+    re.re.captures(sub, &mut caps);
+    if !caps.is_match() {
+        return 0;
+    }
+
+    let slots = unsafe { slice::from_raw_parts_mut(out_slots, slots_len as usize) };
+    for i in 0..group_count {
+        match caps.get_group(i) {
+            Some(span) => {
+                slots[2 * i] = (s + span.start) as c_ulonglong;
+                slots[2 * i + 1] = (s + span.end) as c_ulonglong;
+            }
+            None => {
+                slots[2 * i] = c_ulonglong::MAX;
+                slots[2 * i + 1] = c_ulonglong::MAX;
+            }
+        }
+    }
+    1
+}
+
+// ---------------------- RegexSet ----------------------
+
+#[repr(C)]
+pub struct AifwRegexSet {
+    re: Regex,
+}
+
+/// Compile `count` patterns into a single multi-pattern set.
+/// Returns a handle; returns null on failure.
+#[no_mangle]
+pub extern "C" fn aifw_regex_set_compile(
+    patterns: *const *const c_char,
+    count: c_ulonglong,
+) -> *mut AifwRegexSet {
+    if patterns.is_null() { return core::ptr::null_mut(); }
+
+    let ptrs = unsafe { slice::from_raw_parts(patterns, count as usize) };
+    let mut strs = Vec::with_capacity(ptrs.len());
+    for &p in ptrs {
+        match unsafe { cstr_to_str(p) } {
+            Some(s) => strs.push(s),
+            None => return core::ptr::null_mut(),
+        }
+    }
+
+    // `MatchKind::All` so the overlapping search reports every pattern that
+    // matches, not only those that win under leftmost-first priority.
+    let cfg = meta::Config::new().match_kind(regex_automata::MatchKind::All);
+    let re = match Builder::new().configure(cfg).build_many(&strs) {
+        Ok(r) => r,
+        Err(_) => return core::ptr::null_mut(),
+    };
+    Box::into_raw(Box::new(AifwRegexSet { re }))
+}
+
+#[no_mangle]
+pub extern "C" fn aifw_regex_set_free(ptr_set: *mut AifwRegexSet) {
+    if !ptr_set.is_null() {
+        unsafe { drop(Box::from_raw(ptr_set)); }
+    }
+}
+
+/// Report which patterns in the set match anywhere in the haystack.
+///
+/// `out_which` must have room for `which_len` bytes, one per compiled
+/// pattern; each is set to 1 if that pattern matched, 0 otherwise.
+/// Returns the number of patterns that matched, or < 0 on error.
+#[no_mangle]
+pub extern "C" fn aifw_regex_set_matches(
+    ptr_set: *mut AifwRegexSet,
+    hay_ptr: *const c_uchar,
+    hay_len: c_ulonglong,
+    start: c_ulonglong,
+    out_which: *mut c_uchar,
+    which_len: c_ulonglong,
+) -> c_int {
+    if ptr_set.is_null() || hay_ptr.is_null() || out_which.is_null() {
+        return -1;
+    }
+    let set = unsafe { &*ptr_set };
+    let pattern_len = set.re.pattern_len();
+    if (which_len as usize) < pattern_len {
+        return -2;
+    }
+
+    let hay = unsafe { slice::from_raw_parts(hay_ptr as *const u8, hay_len as usize) };
+    let s = core::cmp::min(start as usize, hay.len());
+
+    let mut patset = PatternSet::new(pattern_len);
+    set.re.which_overlapping_matches(&Input::new(hay).span(s..hay.len()), &mut patset);
+
+    let which = unsafe { slice::from_raw_parts_mut(out_which, which_len as usize) };
+    let mut matched = 0;
+    for i in 0..pattern_len {
+        let hit = patset.contains(PatternID::new_unchecked(i));
+        which[i] = hit as c_uchar;
+        if hit { matched += 1; }
+    }
+    matched
+}
+
+// ---------------------- iterator ----------------------
+
+// Byte length of the UTF-8 sequence starting with `b`, or 1 if `b` does not
+// begin a valid sequence (matches how `find_iter` steps over empty matches).
+fn utf8_len(b: u8) -> usize {
+    if b < 0x80 { 1 }
+    else if b & 0xE0 == 0xC0 { 2 }
+    else if b & 0xF0 == 0xE0 { 3 }
+    else if b & 0xF8 == 0xF0 { 4 }
+    else { 1 }
+}
+
+#[repr(C)]
+pub struct AifwRegexIter {
+    ptr_re: *const AifwRegex,
+    hay_ptr: *const c_uchar,
+    hay_len: usize,
+    cursor: usize,
+    // End of the last reported match, or usize::MAX before the first one.
+    // Needed to suppress an empty match immediately after a non-empty one,
+    // matching `find_iter`'s non-overlapping semantics.
+    last_match_end: usize,
+}
+
+/// Create an iterator over non-overlapping matches of `ptr_re` in the
+/// haystack. `ptr_re` and the haystack must outlive the iterator.
+#[no_mangle]
+pub extern "C" fn aifw_regex_iter_new(
+    ptr_re: *mut AifwRegex,
+    hay_ptr: *const c_uchar,
+    hay_len: c_ulonglong,
+) -> *mut AifwRegexIter {
+    if ptr_re.is_null() || hay_ptr.is_null() {
+        return core::ptr::null_mut();
+    }
+    Box::into_raw(Box::new(AifwRegexIter {
+        ptr_re,
+        hay_ptr,
+        hay_len: hay_len as usize,
+        cursor: 0,
+        last_match_end: usize::MAX,
+    }))
+}
+
+#[no_mangle]
+pub extern "C" fn aifw_regex_iter_free(iter: *mut AifwRegexIter) {
+    if !iter.is_null() {
+        unsafe { drop(Box::from_raw(iter)); }
+    }
+}
+
+/// Advance the iterator to the next non-overlapping match.
+/// Returns 1 and fills `out_start`/`out_end` on a match, 0 once the
+/// haystack is exhausted, and < 0 on error.
+#[no_mangle]
+pub extern "C" fn aifw_regex_iter_next(
+    iter: *mut AifwRegexIter,
+    out_start: *mut c_ulonglong,
+    out_end: *mut c_ulonglong,
+) -> c_int {
+    if iter.is_null() || out_start.is_null() || out_end.is_null() {
+        return -1;
+    }
+    let it = unsafe { &mut *iter };
+    if it.ptr_re.is_null() {
+        return 0;
+    }
+    let re = unsafe { &*it.ptr_re };
+    let hay = unsafe { slice::from_raw_parts(it.hay_ptr, it.hay_len) };
+
+    loop {
+        if it.cursor > it.hay_len {
+            return 0;
+        }
+        let m = match re.re.find(Input::new(hay).span(it.cursor..it.hay_len)) {
+            Some(m) => m,
+            None => {
+                it.cursor = it.hay_len + 1;
+                return 0;
+            }
+        };
+        let start = m.start();
+        let end = m.end();
+        if start == end {
+            it.cursor = end + utf8_len(hay.get(end).copied().unwrap_or(0));
+            // An empty match right where the previous match ended is not a
+            // new match per `find_iter` semantics; skip and keep searching.
+            if it.last_match_end == end {
+                continue;
+            }
+        } else {
+            it.cursor = end;
+        }
+        it.last_match_end = end;
+        unsafe {
+            *out_start = start as c_ulonglong;
+            *out_end = end as c_ulonglong;
+        }
+        return 1;
+    }
+}
+
+// ---------------------- replace ----------------------
+
+// Accumulates bytes into a caller-provided buffer, tracking the full length
+// that would be needed even once the buffer fills up (so callers can size a
+// retry without a second pass).
+struct Cursor<'a> {
+    buf: &'a mut [u8],
+    written: usize,
+    needed: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Cursor { buf, written: 0, needed: 0 }
+    }
+
+    fn push(&mut self, bytes: &[u8]) {
+        self.needed += bytes.len();
+        if self.written < self.buf.len() {
+            let room = self.buf.len() - self.written;
+            let n = core::cmp::min(room, bytes.len());
+            self.buf[self.written..self.written + n].copy_from_slice(&bytes[..n]);
+            self.written += n;
+        }
+    }
+}
+
+// Lets `write!(cursor, "{}", err)` format a `Display` error straight into
+// the buffer without allocating, per this crate's `no_std` constraint.
+impl<'a> core::fmt::Write for Cursor<'a> {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+// Interpret `repl` as a regex-crate-style expansion template against `caps`,
+// writing the result (`$$` becomes a literal `$`; unrecognized `$` sequences
+// are passed through) into `cur`.
+fn expand_template(repl: &[u8], caps: &Captures, group_info: &regex_automata::util::captures::GroupInfo, hay: &[u8], cur: &mut Cursor) {
+    let mut i = 0;
+    while i < repl.len() {
+        if repl[i] != b'$' {
+            cur.push(&repl[i..i + 1]);
+            i += 1;
+            continue;
+        }
+        if i + 1 >= repl.len() {
+            cur.push(&repl[i..i + 1]);
+            i += 1;
+            continue;
+        }
+        if repl[i + 1] == b'$' {
+            cur.push(b"$");
+            i += 2;
+            continue;
+        }
+        if repl[i + 1] == b'{' {
+            match repl[i + 2..].iter().position(|&b| b == b'}') {
+                Some(close) => {
+                    let name = &repl[i + 2..i + 2 + close];
+                    write_group(name, group_info, caps, hay, cur);
+                    i += 2 + close + 1;
+                }
+                None => {
+                    // unterminated `${`; pass through literally
+                    cur.push(&repl[i..i + 1]);
+                    i += 1;
+                }
+            }
+            continue;
+        }
+        let start = i + 1;
+        let mut j = start;
+        while j < repl.len() && (repl[j].is_ascii_alphanumeric() || repl[j] == b'_') {
+            j += 1;
+        }
+        if j == start {
+            cur.push(&repl[i..i + 1]);
+            i += 1;
+            continue;
+        }
+        write_group(&repl[start..j], group_info, caps, hay, cur);
+        i = j;
+    }
+}
+
+// Resolve a `$name`/`$N` reference and push its matched text; an empty
+// string if the group exists but didn't participate in the match, or the
+// original `$name` text if no such group exists (group 0 is always known).
+fn write_group(name: &[u8], group_info: &regex_automata::util::captures::GroupInfo, caps: &Captures, hay: &[u8], cur: &mut Cursor) {
+    let idx = match str::from_utf8(name) {
+        Ok(s) => match s.parse::<usize>() {
+            Ok(n) => Some(n),
+            Err(_) => group_info.to_index(PatternID::ZERO, s),
+        },
+        Err(_) => None,
+    };
+    match idx {
+        Some(i) if i < group_info.group_len(PatternID::ZERO) => {
+            if let Some(span) = caps.get_group(i) {
+                cur.push(&hay[span.start..span.end]);
+            }
+        }
+        _ => {
+            cur.push(b"$");
+            cur.push(name);
+        }
+    }
+}
+
+/// Replace the first match in the haystack using a `$N`/`$name` expansion
+/// template (see the regex crate's `Regex::replace` semantics), writing
+/// prefix + expansion + suffix into `out_buf`.
+///
+/// Returns 1 on a successful write, 0 if there was no match (`out_buf` is
+/// left untouched), and < 0 on error, including -2 when `out_cap` is too
+/// small — `*out_written` always reports the length that was needed so the
+/// caller can resize and retry.
+#[no_mangle]
+pub extern "C" fn aifw_regex_replace(
+    ptr_re: *mut AifwRegex,
+    hay_ptr: *const c_uchar,
+    hay_len: c_ulonglong,
+    replacement_ptr: *const c_uchar,
+    replacement_len: c_ulonglong,
+    out_buf: *mut c_uchar,
+    out_cap: c_ulonglong,
+    out_written: *mut c_ulonglong,
+) -> c_int {
+    if ptr_re.is_null() || hay_ptr.is_null() || replacement_ptr.is_null() || out_written.is_null() {
+        return -1;
+    }
+    if out_buf.is_null() && out_cap != 0 {
+        return -1;
+    }
+    let re = unsafe { &*ptr_re };
+    let hay = unsafe { slice::from_raw_parts(hay_ptr as *const u8, hay_len as usize) };
+    let repl = unsafe { slice::from_raw_parts(replacement_ptr, replacement_len as usize) };
+
+    let mut caps = re.re.create_captures();
+    re.re.captures(hay, &mut caps);
+    let m = match caps.get_match() {
+        Some(m) => m,
+        None => return 0,
+    };
+
+    let out: &mut [u8] = if out_cap == 0 { &mut [] } else { unsafe { slice::from_raw_parts_mut(out_buf, out_cap as usize) } };
+    let mut cur = Cursor::new(out);
+    cur.push(&hay[..m.start()]);
+    expand_template(repl, &caps, re.re.group_info(), hay, &mut cur);
+    cur.push(&hay[m.end()..]);
+
+    unsafe { *out_written = cur.needed as c_ulonglong; }
+    if cur.written < cur.needed { -2 } else { 1 }
+}
+
+/// Like `aifw_regex_replace`, but replaces every non-overlapping match.
+/// Unlike it, when nothing matches the haystack is still copied unchanged
+/// into `out_buf` (with `*out_written` set) before 0 is returned.
+#[no_mangle]
+pub extern "C" fn aifw_regex_replace_all(
+    ptr_re: *mut AifwRegex,
+    hay_ptr: *const c_uchar,
+    hay_len: c_ulonglong,
+    replacement_ptr: *const c_uchar,
+    replacement_len: c_ulonglong,
+    out_buf: *mut c_uchar,
+    out_cap: c_ulonglong,
+    out_written: *mut c_ulonglong,
+) -> c_int {
+    if ptr_re.is_null() || hay_ptr.is_null() || replacement_ptr.is_null() || out_written.is_null() {
+        return -1;
+    }
+    if out_buf.is_null() && out_cap != 0 {
+        return -1;
+    }
+    let re = unsafe { &*ptr_re };
+    let hay = unsafe { slice::from_raw_parts(hay_ptr as *const u8, hay_len as usize) };
+    let repl = unsafe { slice::from_raw_parts(replacement_ptr, replacement_len as usize) };
+
+    let out: &mut [u8] = if out_cap == 0 { &mut [] } else { unsafe { slice::from_raw_parts_mut(out_buf, out_cap as usize) } };
+    let mut cur = Cursor::new(out);
+    let mut caps = re.re.create_captures();
+    let mut last = 0usize;
+    let mut cursor = 0usize;
+    let mut last_match_end = usize::MAX;
+    let mut any = false;
+    while cursor <= hay.len() {
+        re.re.captures(Input::new(hay).span(cursor..hay.len()), &mut caps);
+        let m = match caps.get_match() {
+            Some(m) => m,
+            None => break,
+        };
+        if m.start() == m.end() {
+            cursor = m.end() + utf8_len(hay.get(m.end()).copied().unwrap_or(0));
+            // An empty match right where the previous match ended is not a
+            // new match per `Regex::replace`'s non-overlapping semantics;
+            // skip it instead of expanding the template again.
+            if last_match_end == m.end() {
+                continue;
+            }
+        } else {
+            cursor = m.end();
+        }
+        last_match_end = m.end();
+        any = true;
+        cur.push(&hay[last..m.start()]);
+        expand_template(repl, &caps, re.re.group_info(), hay, &mut cur);
+        last = m.end();
+    }
+    cur.push(&hay[last..]);
+
+    unsafe { *out_written = cur.needed as c_ulonglong; }
+    if !any {
+        0
+    } else if cur.written < cur.needed {
+        -2
+    } else {
+        1
+    }
+}